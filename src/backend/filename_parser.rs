@@ -0,0 +1,108 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref SEASON_EPISODE_RE: Regex = Regex::new(r"(?i)s(\d{1,2})[._ -]*e(\d{1,3})").unwrap();
+    static ref ALT_FORMAT_RE: Regex = Regex::new(r"\b(\d{1,2})x(\d{2,3})\b").unwrap();
+    static ref WORDS_FORMAT_RE: Regex = Regex::new(r"(?i)season[._ -]*(\d{1,2}).*?(?:episode|ep|e)[._ -]*(\d{1,3})").unwrap();
+    static ref ABSOLUTE_FALLBACK_RE: Regex = Regex::new(r"\b(\d{1,3})\b").unwrap();
+}
+
+/// The result of scanning a filename stem for season/episode information.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParsedEpisode {
+    pub season:        Option<usize>,
+    pub episode:       usize,
+    pub series_guess:  String,
+}
+
+/// Scans a filename stem (no extension) with an ordered set of regexes to recover the
+/// season/episode numbering that is already encoded in the name, e.g. `S01E02`, `1x02`,
+/// or `Season 1 Episode 2`. Falls back to a bare absolute episode number if nothing else
+/// matches. Returns `None` when no pattern recognizes the stem at all.
+pub fn parse(stem: &str) -> Option<ParsedEpisode> {
+    let patterns: [(&Regex, bool); 4] = [
+        (&*SEASON_EPISODE_RE, true),
+        (&*ALT_FORMAT_RE, true),
+        (&*WORDS_FORMAT_RE, true),
+        (&*ABSOLUTE_FALLBACK_RE, false),
+    ];
+
+    for (pattern, has_season) in &patterns {
+        if let Some(captures) = pattern.captures(stem) {
+            let whole = captures.get(0).unwrap();
+            let series_guess = series_guess_from(stem, whole.start());
+
+            return if *has_season {
+                let season = captures.get(1).and_then(|m| m.as_str().parse::<usize>().ok());
+                let episode = captures.get(2).and_then(|m| m.as_str().parse::<usize>().ok())?;
+                Some(ParsedEpisode { season, episode, series_guess })
+            } else {
+                let episode = captures.get(1).and_then(|m| m.as_str().parse::<usize>().ok())?;
+                Some(ParsedEpisode { season: None, episode, series_guess })
+            };
+        }
+    }
+
+    None
+}
+
+/// Takes everything before the first match and collapses `.`/`_` separators into spaces.
+fn series_guess_from(stem: &str, match_start: usize) -> String {
+    stem[..match_start]
+        .replace('.', " ")
+        .replace('_', " ")
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_s01e02() {
+        let parsed = parse("Some.Show.S01E02.720p").unwrap();
+        assert_eq!(parsed.season, Some(1));
+        assert_eq!(parsed.episode, 2);
+        assert_eq!(parsed.series_guess, "Some Show");
+    }
+
+    #[test]
+    fn test_parse_1x02() {
+        let parsed = parse("Some Show 1x02").unwrap();
+        assert_eq!(parsed.season, Some(1));
+        assert_eq!(parsed.episode, 2);
+    }
+
+    #[test]
+    fn test_parse_season_episode_words() {
+        let parsed = parse("Some Show Season 2 Episode 05").unwrap();
+        assert_eq!(parsed.season, Some(2));
+        assert_eq!(parsed.episode, 5);
+    }
+
+    #[test]
+    fn test_parse_absolute_fallback() {
+        let parsed = parse("Some Show - 013").unwrap();
+        assert_eq!(parsed.season, None);
+        assert_eq!(parsed.episode, 13);
+    }
+
+    #[test]
+    fn test_parse_no_match() {
+        assert_eq!(parse("Some Show"), None);
+    }
+
+    #[test]
+    fn test_parse_does_not_mistake_resolution_for_episode() {
+        assert_eq!(parse("Some.Show.1920x1080.mkv"), None);
+        assert_eq!(parse("Some.Show.1280x720"), None);
+    }
+
+    #[test]
+    fn test_parse_does_not_mistake_year_or_resolution_tag_for_absolute() {
+        assert_eq!(parse("Some Show 2009"), None);
+        assert_eq!(parse("Some Show 720p"), None);
+    }
+}
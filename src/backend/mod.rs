@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::env;
 use std::ffi::OsStr;
 use std::fs;
@@ -6,11 +7,70 @@ use std::path::{Path, PathBuf};
 pub mod logging;
 pub mod traits;
 pub mod tokenizer;
+pub mod filename_parser;
+pub mod providers;
 
 use self::traits::Digits;
 use self::tokenizer::TemplateToken;
+use self::filename_parser::ParsedEpisode;
+use self::providers::{MetadataProvider, SeriesId, TvdbProvider, TmdbProvider};
 
-use tvdb;
+/// Names the numbering scheme of the episode numbers passed to a title lookup. The TVDB
+/// and TMDB providers look titles up by aired season/episode numbers in every case.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EpisodeOrder {
+    // The order episodes originally aired in -- the default.
+    Aired,
+    // The order episodes were released on DVD/Blu-ray, which often differs from air order.
+    Dvd,
+    // A single flat count across the whole series, ignoring season boundaries.
+    Absolute,
+}
+
+impl Default for EpisodeOrder {
+    fn default() -> EpisodeOrder { EpisodeOrder::Aired }
+}
+
+/// File extensions recognized as video episodes by `get_episodes`. Anything else
+/// (subtitles, `.nfo` metadata, samples) is left out of the episode list.
+pub const VIDEO_EXTENSIONS: &[&str] = &[
+    "mkv", "mp4", "avi", "webm", "mov", "wmv", "flv", "m4v", "ts", "mpg"
+];
+
+/// The metadata backend to fetch episode titles from. An enum rather than a bare
+/// `Box<dyn MetadataProvider>` so `Arguments` can keep deriving `Clone`/`Debug`.
+#[derive(Clone, Debug)]
+pub enum Provider {
+    Tvdb(TvdbProvider),
+    Tmdb(TmdbProvider),
+}
+
+impl Default for Provider {
+    fn default() -> Provider { Provider::Tvdb(TvdbProvider::default()) }
+}
+
+impl MetadataProvider for Provider {
+    fn search(&self, series: &str, language: &str) -> Result<SeriesId, String> {
+        match self {
+            Provider::Tvdb(provider) => provider.search(series, language),
+            Provider::Tmdb(provider) => provider.search(series, language),
+        }
+    }
+
+    fn episode_title(&self, series: &SeriesId, season: usize, episode: usize, order: EpisodeOrder) -> Result<String, String> {
+        match self {
+            Provider::Tvdb(provider) => provider.episode_title(series, season, episode, order),
+            Provider::Tmdb(provider) => provider.episode_title(series, season, episode, order),
+        }
+    }
+}
+
+fn is_video_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .map(|ext| VIDEO_EXTENSIONS.iter().any(|video_ext| ext.eq_ignore_ascii_case(video_ext)))
+        .unwrap_or(false)
+}
 
 #[derive(Clone, Debug)]
 pub struct Arguments {
@@ -42,30 +102,111 @@ pub struct Arguments {
     pub pad_length:    usize,
 
     // The template used for setting the naming scheme of episodes.
-    pub template:      Vec<TemplateToken>
+    pub template:      Vec<TemplateToken>,
+
+    // The numbering scheme of the episode numbers, passed to the provider's title lookup
+    // (the TVDB and TMDB providers match by aired order whatever this is).
+    pub episode_order: EpisodeOrder,
+
+    // Descend into subdirectories, treating each as its own season, instead of renaming
+    // a single flat directory.
+    pub recursive:     bool,
+
+    // Limits how many levels the recursive walk descends. `None` means unbounded.
+    pub max_depth:     Option<usize>,
+
+    // Carry along sibling subtitle/metadata files (e.g. `.srt`, `.nfo`) so they keep
+    // pointing at their episode after it is renamed.
+    pub rename_companions: bool,
+
+    // The metadata backend to fetch episode titles from.
+    pub provider:      Provider,
+
+    // The language to request episode titles in. Defaults to "en"; when a title lookup
+    // fails in another language, falls back to `DEFAULT_LANGUAGE` ("en"), not to the
+    // series' own original language. The TMDB provider sends it with the series search
+    // only; its episode-title request carries no language.
+    pub language:      String
+}
+
+impl Default for Arguments {
+    /// Sensible defaults for the options added alongside `EpisodeOrder`, recursion,
+    /// companion renaming, and the provider/language lookups, so existing callers can
+    /// opt in with `Arguments { series_name: ..., ..Default::default() }` instead of
+    /// having to name every new field.
+    fn default() -> Arguments {
+        Arguments {
+            automatic:     false,
+            dry_run:       false,
+            log_changes:   false,
+            verbose:       false,
+            directory:     String::new(),
+            series_name:   String::new(),
+            season_number: 1,
+            episode_count: 1,
+            pad_length:    2,
+            template:      Vec::new(),
+            episode_order: EpisodeOrder::default(),
+            recursive:     false,
+            max_depth:     None,
+            rename_companions: false,
+            provider:      Provider::default(),
+            language:      String::from(DEFAULT_LANGUAGE),
+        }
+    }
 }
 
+/// The language `episode_title` falls back to when a title isn't available in the
+/// requested `Arguments::language`.
+const DEFAULT_LANGUAGE: &str = "en";
+
 impl Arguments {
-    /// Given a source of episodes from a directory, this returns a list of their target paths.
-    pub fn get_targets(&self, directory: &str, episodes: &[PathBuf], episode_index: usize) -> Result<Vec<PathBuf>, String> {
-        let api = tvdb::Tvdb::new("0629B785CE550C8D");
+    /// Given a source of episodes from a directory, this returns the source/target path
+    /// pairs that make up the rename plan. When `rename_companions` is set, sibling
+    /// subtitle/metadata files sharing an episode's stem are included in the plan too.
+    pub fn get_targets(&self, directory: &str, episodes: &[PathBuf], episode_index: usize) -> Result<Vec<(PathBuf, PathBuf)>, String> {
         let series_info = if self.template.contains(&TemplateToken::TVDB) {
-            match api.search(self.series_name.as_str(), "en") {
-                Ok(reply) => Some(reply),
-                Err(_) => { return Err(String::from("unable to get TVDB series information")); }
-            }
+            Some(self.provider.search(self.series_name.as_str(), self.language.as_str())?)
         } else {
             None
         };
 
-        let mut output: Vec<PathBuf> = Vec::new();
+        // Resolved lazily, and only when a title turns out to be missing in `self.language`.
+        let mut fallback_series_info: Option<SeriesId> = None;
+
+        let mut output: Vec<(PathBuf, PathBuf)> = Vec::new();
         let mut current_index = episode_index;
         for file in episodes {
+            // Recover the real season/episode numbering from the filename itself, if present,
+            // rather than trusting the directory's sequential order.
+            let parsed: Option<ParsedEpisode> = file.file_stem()
+                .and_then(OsStr::to_str)
+                .and_then(filename_parser::parse);
+
+            let season_number = parsed.as_ref().and_then(|p| p.season).unwrap_or(self.season_number);
+            let episode_number = parsed.as_ref().map(|p| p.episode).unwrap_or(current_index);
+
             // TVDB Titles
             let tvdb_title = if self.template.contains(&TemplateToken::TVDB) {
-                let reply = series_info.clone().unwrap();
-                match api.episode(&reply[0], self.season_number as u32, current_index as u32) {
-                    Ok(episode) => episode.episode_name,
+                let series = series_info.as_ref().unwrap();
+                let lookup = self.provider.episode_title(series, season_number, episode_number, self.episode_order);
+
+                // Missing in the requested language? Fall back to the default language
+                // instead of failing the whole rename.
+                let lookup = if lookup.is_err() && self.language != DEFAULT_LANGUAGE {
+                    if fallback_series_info.is_none() {
+                        fallback_series_info = self.provider.search(self.series_name.as_str(), DEFAULT_LANGUAGE).ok();
+                    }
+                    match fallback_series_info.as_ref() {
+                        Some(fallback) => self.provider.episode_title(fallback, season_number, episode_number, self.episode_order),
+                        None => lookup,
+                    }
+                } else {
+                    lookup
+                };
+
+                match lookup {
+                    Ok(title) => title,
                     Err(_) => { return Err(format!("episode '{}' does not exist", file.to_string_lossy())); }
                 }
             } else {
@@ -73,42 +214,216 @@ impl Arguments {
             };
 
             // Get target destination for the current file.
-            let new_destination = self.get_destination(Path::new(directory), file, current_index, &tvdb_title);
-            output.push(new_destination);
+            let new_destination = self.get_destination(Path::new(directory), file, season_number, episode_number, &tvdb_title);
+            output.push((file.clone(), new_destination));
+
+            if self.rename_companions {
+                let base_name = self.render_filename(season_number, episode_number, &tvdb_title);
+                for companion in find_companions(file) {
+                    if let Some(suffix) = companion_suffix(file, &companion) {
+                        let companion_target = Path::new(directory).join(format!("{}.{}", base_name, suffix));
+                        output.push((companion, companion_target));
+                    }
+                }
+            }
+
             current_index += 1;
         }
         Ok(output)
     }
 
+    /// Walks `root` recursively (depth bounded by `self.max_depth`), treating each
+    /// discovered subdirectory as its own season, and returns a combined rename plan of
+    /// source/target path pairs across the whole tree. Directories whose season number
+    /// can't be derived are skipped when `self.automatic` is set; otherwise they fall
+    /// back to `self.season_number`.
+    pub fn get_recursive_targets(&self, root: &str) -> Result<Vec<(PathBuf, PathBuf)>, String> {
+        let mut plan = Vec::new();
+        for season_dir in walk_directories(Path::new(root), self.max_depth) {
+            let season_number = match derive_season_number(&season_dir) {
+                Some(number) => number,
+                None if self.automatic => continue,
+                None => self.season_number,
+            };
+
+            let directory = season_dir.to_string_lossy().into_owned();
+            let episodes = get_episodes(&directory)?;
+            if episodes.is_empty() {
+                continue;
+            }
+
+            let mut args = self.clone();
+            args.season_number = season_number;
+            let targets = args.get_targets(&directory, &episodes, self.episode_count)?;
+            plan.extend(targets);
+        }
+        Ok(plan)
+    }
+
+    /// Validates and then performs a rename plan produced by `get_targets` or
+    /// `get_recursive_targets`.
+    ///
+    /// The whole batch is validated up front: duplicate targets are rejected, as are
+    /// pre-existing targets (unless `overwrite` is set) and case-insensitive collisions.
+    /// Every source is then staged under a temporary name before any final rename happens,
+    /// so cycles like `a -> b, b -> a` never clobber a file that hasn't moved yet. If a
+    /// rename fails midway, every already-moved file is reverted to its original name.
+    /// Actual filesystem mutation only happens when `self.dry_run` is false; either way,
+    /// the recorded journal (what was actually renamed, or what would have been) is fed
+    /// to `logging` when `self.log_changes` is set.
+    pub fn apply_plan(&self, plan: &[(PathBuf, PathBuf)], overwrite: bool) -> Result<(), String> {
+        validate_plan(plan, overwrite)?;
+
+        let journal = if self.dry_run {
+            plan.to_vec()
+        } else {
+            apply_plan_moves(plan)?
+        };
+
+        if self.log_changes {
+            logging::log_changes(&journal);
+        }
+
+        Ok(())
+    }
+
     /// Obtain the target path of the file based on the episode count
-    pub fn get_destination(&self, directory: &Path, file: &Path, episode: usize, title: &str) -> PathBuf {
-        let mut destination = String::from(directory.to_str().unwrap());
-        destination.push('/');
+    pub fn get_destination(&self, directory: &Path, file: &Path, season: usize, episode: usize, title: &str) -> PathBuf {
+        let mut filename = self.render_filename(season, episode, title);
+
+        // Append the extension
+        let extension = file.extension().unwrap_or_else(|| OsStr::new("")).to_str().unwrap_or("");
+        if !extension.is_empty() {
+            filename.push('.');
+            filename.push_str(extension);
+        }
 
+        directory.join(filename)
+    }
+
+    /// Renders the template into a base filename (no extension), sanitized for use on disk.
+    fn render_filename(&self, season: usize, episode: usize, title: &str) -> String {
         let mut filename = String::new();
         for pattern in self.template.clone() {
             match pattern {
                 TemplateToken::Character(value) => filename.push(value),
                 TemplateToken::Series  => filename.push_str(self.series_name.clone().as_str()),
-                TemplateToken::Season  => filename.push_str(self.season_number.to_string().as_str()),
+                TemplateToken::Season  => filename.push_str(season.to_string().as_str()),
                 TemplateToken::Episode => filename.push_str(episode.to_padded_string('0', self.pad_length).as_str()),
                 TemplateToken::TVDB    => filename.push_str(title),
             }
         }
         filename = String::from(filename.trim()); // Remove extra spaces
-        filename = filename.replace("/", "-");     // Remove characters that are invalid in pathnames
+        filename.replace("/", "-")                 // Remove characters that are invalid in pathnames
+    }
+}
 
-        // Append the extension
-        let extension = file.extension().unwrap_or_else(|| OsStr::new("")).to_str().unwrap_or("");
-        if !extension.is_empty() {
-            filename.push('.');
-            filename.push_str(extension);
+/// Finds sibling files in the same directory as `file` that share its stem (e.g.
+/// `Episode.en.srt`, `Episode.nfo` alongside `Episode.mkv`).
+fn find_companions(file: &Path) -> Vec<PathBuf> {
+    let stem = match file.file_stem().and_then(OsStr::to_str) {
+        Some(stem) => stem,
+        None => return Vec::new(),
+    };
+    let parent = file.parent().unwrap_or_else(|| Path::new("."));
+    let prefix = format!("{}.", stem);
+
+    let mut companions = Vec::new();
+    if let Ok(entries) = fs::read_dir(parent) {
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            // Another video sharing this stem as a prefix (e.g. `Episode.mkv` next to
+            // `Episode.sample.mkv`) is its own episode, not a companion of this one.
+            if path == file || is_video_file(&path) {
+                continue;
+            }
+            if path.file_name().and_then(OsStr::to_str).map_or(false, |name| name.starts_with(&prefix)) {
+                companions.push(path);
+            }
+        }
+    }
+    companions
+}
+
+/// Returns the portion of `companion`'s filename after the video's stem, e.g. `en.srt`
+/// for `Episode.en.srt` alongside `Episode.mkv`.
+fn companion_suffix(file: &Path, companion: &Path) -> Option<String> {
+    let stem = file.file_stem().and_then(OsStr::to_str)?;
+    let name = companion.file_name().and_then(OsStr::to_str)?;
+    let prefix = format!("{}.", stem);
+    if name.starts_with(&prefix) {
+        Some(name[prefix.len()..].to_string())
+    } else {
+        None
+    }
+}
+
+/// Rejects a rename plan that would clobber files: duplicate targets (including
+/// case-insensitive collisions, since many of the filesystems this tool targets are
+/// case-insensitive) and targets that already exist on disk and aren't themselves one of
+/// the plan's sources (i.e. about to be moved out of the way).
+fn validate_plan(plan: &[(PathBuf, PathBuf)], overwrite: bool) -> Result<(), String> {
+    let sources: HashSet<String> = plan.iter()
+        .map(|(source, _)| source.to_string_lossy().to_lowercase())
+        .collect();
+
+    let mut seen_targets: HashSet<String> = HashSet::new();
+    for (_, target) in plan {
+        let key = target.to_string_lossy().to_lowercase();
+        if !seen_targets.insert(key.clone()) {
+            return Err(format!("multiple files would be renamed to '{}'", target.to_string_lossy()));
+        }
+
+        if !overwrite && target.exists() && !sources.contains(&key) {
+            return Err(format!("target '{}' already exists", target.to_string_lossy()));
         }
+    }
+    Ok(())
+}
 
-        // Return the path as a PathBuf
-        destination.push_str(&filename);
-        PathBuf::from(destination)
+/// Performs a validated rename plan on disk, staging every source under a temporary name
+/// first so that overlapping or cyclic targets never collide with an unmoved file. If any
+/// step fails, every rename already performed is reverted. On success, returns the journal
+/// of original/target pairs that were actually renamed.
+fn apply_plan_moves(plan: &[(PathBuf, PathBuf)]) -> Result<Vec<(PathBuf, PathBuf)>, String> {
+    let mut staged: Vec<(PathBuf, PathBuf)> = Vec::new(); // (original, temp)
+    for (index, (source, _)) in plan.iter().enumerate() {
+        let temp = temp_name_for(source, index);
+        if let Err(error) = fs::rename(source, &temp) {
+            revert(staged.iter().map(|(original, temp)| (temp, original)));
+            return Err(format!("failed to stage '{}': {}", source.to_string_lossy(), error));
+        }
+        staged.push((source.clone(), temp));
     }
+
+    let mut finished: Vec<(PathBuf, PathBuf)> = Vec::new(); // (original, target)
+    for ((original, temp), (_, target)) in staged.iter().zip(plan.iter()) {
+        if let Err(error) = fs::rename(temp, target) {
+            // Finished renames go straight back from their target to their original name
+            // (the temp name is already gone); renames still waiting under a temp name
+            // also go back to their original name.
+            revert(finished.iter().map(|(original, target)| (target, original)));
+            revert(staged[finished.len()..].iter().map(|(original, temp)| (temp, original)));
+            return Err(format!("failed to rename '{}' to '{}': {}", original.to_string_lossy(), target.to_string_lossy(), error));
+        }
+        finished.push((original.clone(), target.clone()));
+    }
+
+    Ok(finished)
+}
+
+/// Renames every `(from, to)` pair back from `from` to `to`, best-effort, used to roll
+/// back a partially-applied plan.
+fn revert<'a, I: Iterator<Item = (&'a PathBuf, &'a PathBuf)>>(moves: I) {
+    for (from, to) in moves {
+        let _ = fs::rename(from, to);
+    }
+}
+
+/// A temporary, unique-per-plan name for staging `source`'s rename in its own directory.
+fn temp_name_for(source: &Path, index: usize) -> PathBuf {
+    let parent = source.parent().unwrap_or_else(|| Path::new("."));
+    parent.join(format!(".tv-renamer-tmp-{}", index))
 }
 
 /// Takes a pathname and shortens it for readability.
@@ -146,6 +461,29 @@ pub fn derive_season_number(season: &Path) -> Option<usize> {
     }
 }
 
+/// Recursively collects directories under `root`, descending up to `max_depth` levels
+/// (unbounded when `None`). Used to discover per-season directories in a show's root.
+pub fn walk_directories(root: &Path, max_depth: Option<usize>) -> Vec<PathBuf> {
+    let mut directories = Vec::new();
+    walk_directories_at(root, max_depth, 0, &mut directories);
+    directories.sort_by(|a, b| a.to_string_lossy().cmp(&b.to_string_lossy()));
+    directories
+}
+
+fn walk_directories_at(current: &Path, max_depth: Option<usize>, depth: usize, out: &mut Vec<PathBuf>) {
+    if let Ok(entries) = fs::read_dir(current) {
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if path.is_dir() {
+                out.push(path.clone());
+                if max_depth.map_or(true, |max| depth < max) {
+                    walk_directories_at(&path, max_depth, depth + 1, out);
+                }
+            }
+        }
+    }
+}
+
 /// Collects a list of all of the seasons in a given directory.
 pub fn get_seasons(directory: &str) -> Result<Vec<PathBuf>, &str> {
     if let Ok(files) = fs::read_dir(directory) {
@@ -170,14 +508,15 @@ pub fn get_seasons(directory: &str) -> Result<Vec<PathBuf>, &str> {
     }
 }
 
-/// Collects a list of all of the episodes in a given directory.
+/// Collects a list of all of the episodes in a given directory, limited to files with a
+/// recognized video extension (see `VIDEO_EXTENSIONS`).
 pub fn get_episodes(directory: &str) -> Result<Vec<PathBuf>, &str> {
     if let Ok(files) = fs::read_dir(directory) {
         let mut episodes = Vec::new();
         for entry in files {
             if let Ok(entry) = entry {
                 if let Ok(metadata) = entry.metadata() {
-                    if metadata.is_file() { episodes.push(entry.path()); }
+                    if metadata.is_file() && is_video_file(&entry.path()) { episodes.push(entry.path()); }
                 } else {
                     return Err("unable to get metadata");
                 }
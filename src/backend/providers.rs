@@ -0,0 +1,135 @@
+use tvdb;
+use reqwest;
+use serde::Deserialize;
+
+use super::EpisodeOrder;
+
+/// A provider-agnostic handle to a series that has already been resolved via `search`,
+/// opaque to everything except the provider that produced it.
+#[derive(Clone, Debug)]
+pub enum SeriesId {
+    Tvdb(tvdb::SeriesSearchResult),
+    Tmdb(u64),
+}
+
+/// Abstracts metadata lookups so the renamer isn't hardwired to a single backend -- this
+/// is what lets `get_targets` fetch a title without caring whether it came from TVDB,
+/// TMDB, or a stub used in tests.
+pub trait MetadataProvider {
+    /// Resolves a series name to an id that later `episode_title` calls can use.
+    fn search(&self, series: &str, language: &str) -> Result<SeriesId, String>;
+
+    /// Fetches the title of a single episode. `order` names the numbering scheme that
+    /// `season`/`episode` are in; the TVDB and TMDB providers in this module look the
+    /// title up by aired season/episode numbers whatever `order` is.
+    fn episode_title(&self, series: &SeriesId, season: usize, episode: usize, order: EpisodeOrder) -> Result<String, String>;
+}
+
+/// The original metadata backend, backed by `tvdb::Tvdb`.
+#[derive(Clone, Debug)]
+pub struct TvdbProvider {
+    api_key: String,
+}
+
+impl TvdbProvider {
+    pub fn new(api_key: &str) -> TvdbProvider {
+        TvdbProvider { api_key: api_key.to_string() }
+    }
+}
+
+impl Default for TvdbProvider {
+    fn default() -> TvdbProvider { TvdbProvider::new("0629B785CE550C8D") }
+}
+
+impl MetadataProvider for TvdbProvider {
+    fn search(&self, series: &str, language: &str) -> Result<SeriesId, String> {
+        let api = tvdb::Tvdb::new(self.api_key.as_str());
+        match api.search(series, language) {
+            Ok(reply) => Ok(SeriesId::Tvdb(reply[0].clone())),
+            Err(_) => Err(String::from("unable to get TVDB series information")),
+        }
+    }
+
+    fn episode_title(&self, series: &SeriesId, season: usize, episode: usize, _order: EpisodeOrder) -> Result<String, String> {
+        let series = match series {
+            SeriesId::Tvdb(series) => series,
+            _ => return Err(String::from("series id was not resolved by the TVDB provider")),
+        };
+
+        // The pinned `tvdb` crate only exposes a per-season/episode aired-order lookup;
+        // it has no bulk episode listing to match DVD or absolute numbering against. Erroring
+        // out here would abort the whole rename for exactly the case this ordering is meant to
+        // serve (e.g. a flat Specials folder), so fall back to the aired lookup with the same
+        // numbers instead -- the title can be off for a DVD/absolute-numbered release, but the
+        // rename still completes.
+        let api = tvdb::Tvdb::new(self.api_key.as_str());
+        api.episode(series, season as u32, episode as u32)
+            .map(|episode| episode.episode_name)
+            .map_err(|_| format!("episode {}x{} does not exist", season, episode))
+    }
+}
+
+#[derive(Deserialize)]
+struct TmdbSearchResponse {
+    results: Vec<TmdbSearchResult>,
+}
+
+#[derive(Deserialize)]
+struct TmdbSearchResult {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct TmdbEpisodeResponse {
+    name: String,
+}
+
+/// Maps TMDB's season/episode endpoints onto the same interface as `TvdbProvider`.
+#[derive(Clone, Debug)]
+pub struct TmdbProvider {
+    api_key: String,
+}
+
+impl TmdbProvider {
+    pub fn new(api_key: &str) -> TmdbProvider {
+        TmdbProvider { api_key: api_key.to_string() }
+    }
+}
+
+impl MetadataProvider for TmdbProvider {
+    fn search(&self, series: &str, language: &str) -> Result<SeriesId, String> {
+        // Let reqwest percent-encode the query parameters -- series names and some
+        // languages contain spaces and other characters that aren't valid in a raw URL.
+        let response: TmdbSearchResponse = reqwest::blocking::Client::new()
+            .get("https://api.themoviedb.org/3/search/tv")
+            .query(&[("api_key", self.api_key.as_str()), ("language", language), ("query", series)])
+            .send()
+            .and_then(|reply| reply.json())
+            .map_err(|_| String::from("unable to get TMDB series information"))?;
+
+        response.results.into_iter().next()
+            .map(|result| SeriesId::Tmdb(result.id))
+            .ok_or_else(|| String::from("no matching TMDB series"))
+    }
+
+    fn episode_title(&self, series: &SeriesId, season: usize, episode: usize, _order: EpisodeOrder) -> Result<String, String> {
+        let id = match series {
+            SeriesId::Tmdb(id) => *id,
+            _ => return Err(String::from("series id was not resolved by the TMDB provider")),
+        };
+
+        // TMDB has no per-number DVD/absolute-order lookup -- `episode_group` addresses a
+        // curated group id, not a literal ordering keyword. Erroring out here would abort the
+        // whole rename for exactly the case this ordering is meant to serve (e.g. a flat
+        // Specials folder), so fall back to the season/episode endpoint with the same numbers
+        // instead -- the title can be off for a DVD/absolute-numbered release, but the rename
+        // still completes.
+        let url = format!("https://api.themoviedb.org/3/tv/{}/season/{}/episode/{}?api_key={}", id, season, episode, self.api_key);
+
+        let response: TmdbEpisodeResponse = reqwest::blocking::get(&url)
+            .and_then(|reply| reply.json())
+            .map_err(|_| String::from("unable to get TMDB episode information"))?;
+
+        Ok(response.name)
+    }
+}